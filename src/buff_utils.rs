@@ -1,141 +1,139 @@
-pub struct BufferInfo<const NUM_T: u64, T: bytemuck::Pod> {
-    usage: wgpu::BufferUsages,
-    shader_buffer: wgpu::Buffer,
-    shader_buffer_label: Option<String>,
-    staging_buffer: wgpu::Buffer,
-    staging_buffer_label: Option<String>,
+use crate::gpu_api::{BindEntry, Buffer, BufferMapError, BufferRole, CommandEncoder, GpuDevice, LayoutEntry};
+use crate::shader_reflect::ShaderReflection;
+
+pub struct BufferInfo<T: bytemuck::Pod> {
+    role: BufferRole,
+    num_elements: u64,
+    shader_buffer: Buffer,
+    staging_buffer: Buffer,
     binding: u32,
     _marker: std::marker::PhantomData<T>,
 }
 
-impl<const NUM_T: u64, T: bytemuck::Pod> BufferInfo<NUM_T, T> {
+impl<T: bytemuck::Pod> BufferInfo<T> {
+    /// `num_elements` is the element count the buffer is sized for; it used
+    /// to be a const generic, but callers now pick it at runtime (e.g. from
+    /// a [`crate::GridConfig`]) so the grid resolution no longer has to be
+    /// known at compile time.
     pub fn new(
-        device: &wgpu::Device,
+        gpu: &GpuDevice,
         label: Option<&str>,
         binding: u32,
-        usage: wgpu::BufferUsages,
+        role: BufferRole,
+        indirect: bool,
+        num_elements: u64,
     ) -> Self {
-        let shader_buffer_label = label.map(|s| format!("{} Shader Buffer", s));
+        let buff_size = num_elements * std::mem::size_of::<T>() as u64;
 
-        // For convenience, I expect the user to provide just BufferUsages::STORAGE or
-        // BufferUsages::UNIFORM.
-        // I will generate the rest of the usages.
-        let shader_buf_usage;
-        let staging_buf_usage;
-        if usage.contains(wgpu::BufferUsages::STORAGE) {
-            shader_buf_usage = wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC;
-            staging_buf_usage = wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ;
-        } else if usage.contains(wgpu::BufferUsages::UNIFORM) {
-            shader_buf_usage = wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST;
-            staging_buf_usage = wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::MAP_WRITE;
-        } else {
-            panic!("Invalid buffer usage");
-        }
+        let shader_buffer_label = label.map(|s| format!("{} Shader Buffer", s));
+        let shader_buffer = gpu.create_shader_buffer(shader_buffer_label.as_deref(), role, indirect, buff_size);
 
-        let buff_size = NUM_T * std::mem::size_of::<T>() as u64;
-        let shader_buffer = device.create_buffer(&wgpu::BufferDescriptor {
-            label: shader_buffer_label.as_deref(),
-            size: buff_size,
-            usage: shader_buf_usage,
-            mapped_at_creation: false,
-        });
         let staging_buffer_label = label.map(|s| format!("{} Staging Buffer", s));
-        let staging_buffer = device.create_buffer(&wgpu::BufferDescriptor {
-            label: staging_buffer_label.as_deref(),
-            size: buff_size,
-            usage: staging_buf_usage,
-            mapped_at_creation: false,
-        });
+        let staging_buffer = gpu.create_staging_buffer(staging_buffer_label.as_deref(), role, buff_size);
+
         Self {
+            role,
+            num_elements,
             shader_buffer,
-            shader_buffer_label,
             staging_buffer,
-            staging_buffer_label,
             binding,
-            usage,
             _marker: std::marker::PhantomData,
         }
     }
-    pub fn get_bind_group_layout_entry(&self) -> wgpu::BindGroupLayoutEntry {
-        let binding_type = if self.usage.contains(wgpu::BufferUsages::STORAGE) {
-            wgpu::BufferBindingType::Storage { read_only: false }
-        } else if self.usage.contains(wgpu::BufferUsages::UNIFORM) {
-            wgpu::BufferBindingType::Uniform
-        } else {
-            panic!("Invalid buffer usage");
-        };
-        wgpu::BindGroupLayoutEntry {
+    pub fn shader_buffer(&self) -> &Buffer {
+        &self.shader_buffer
+    }
+    pub fn byte_size(&self) -> u64 {
+        self.num_elements * std::mem::size_of::<T>() as u64
+    }
+    pub fn get_bind_group_layout_entry(&self) -> LayoutEntry {
+        LayoutEntry {
             binding: self.binding,
-            visibility: wgpu::ShaderStages::COMPUTE,
-            ty: wgpu::BindingType::Buffer {
-                ty: binding_type,
-                has_dynamic_offset: false,
-                min_binding_size: None,
-            },
-            count: None,
+            role: self.role,
         }
     }
-    pub fn get_bind_group_entry(&self) -> wgpu::BindGroupEntry {
-        wgpu::BindGroupEntry {
+    pub fn get_bind_group_entry(&self) -> BindEntry {
+        BindEntry {
             binding: self.binding,
-            resource: self.shader_buffer.as_entire_binding(),
+            buffer: &self.shader_buffer,
         }
     }
-    pub fn copy_to_staging_buffer(&self, command_encoder: &mut wgpu::CommandEncoder) {
-        command_encoder.copy_buffer_to_buffer(
-            &self.shader_buffer,
-            0,
-            &self.staging_buffer,
-            0,
-            NUM_T * std::mem::size_of::<T>() as u64,
-        );
+    pub fn copy_to_staging_buffer(&self, gpu: &GpuDevice, command_encoder: &mut CommandEncoder) {
+        gpu.copy_buffer(command_encoder, &self.shader_buffer, &self.staging_buffer, self.byte_size());
     }
     pub async fn set_uniform_buffer(
         &self,
-        device: &wgpu::Device,
-        command_encoder: &mut wgpu::CommandEncoder,
+        gpu: &GpuDevice,
+        command_encoder: &mut CommandEncoder,
         data: &[T],
-    ) -> Result<(), wgpu::BufferAsyncError> {
-        let buffer_slice = self.staging_buffer.slice(..);
-        let (sender, receiver) = flume::bounded(1);
-        buffer_slice.map_async(wgpu::MapMode::Write, move |r| {
-            sender.send(r).unwrap();
-        });
-        device.poll(wgpu::Maintain::wait()).panic_on_timeout();
-        receiver.recv_async().await.unwrap()?;
-        {
-            let mut view = buffer_slice.get_mapped_range_mut();
-            view.copy_from_slice(bytemuck::cast_slice(data));
-        }
-        self.staging_buffer.unmap();
+    ) -> Result<(), BufferMapError> {
+        gpu.write_mapped(&self.staging_buffer, bytemuck::cast_slice(data))
+            .await?;
 
-        command_encoder.copy_buffer_to_buffer(
-            &self.staging_buffer,
-            0,
-            &self.shader_buffer,
-            0,
-            NUM_T * std::mem::size_of::<T>() as u64,
-        );
+        gpu.copy_buffer(command_encoder, &self.staging_buffer, &self.shader_buffer, self.byte_size());
         Ok(())
     }
-    pub async fn read_staging_buffer(
-        &self,
-        device: &wgpu::Device,
-    ) -> Result<Vec<T>, wgpu::BufferAsyncError> {
-        let mut local_buffer = vec![T::zeroed(); NUM_T as usize];
-        let (sender, receiver) = flume::bounded(1);
-        let buffer_slice = self.staging_buffer.slice(..);
-        buffer_slice.map_async(wgpu::MapMode::Read, move |r| {
-            sender.send(r).unwrap();
-        });
-        device.poll(wgpu::Maintain::Wait).panic_on_timeout();
-        receiver.recv_async().await.unwrap()?;
-        {
-            let view = buffer_slice.get_mapped_range();
-            local_buffer.copy_from_slice(bytemuck::cast_slice(&view));
-        }
-        self.staging_buffer.unmap();
+    pub async fn read_staging_buffer(&self, gpu: &GpuDevice) -> Result<Vec<T>, BufferMapError> {
+        let bytes = gpu.read_mapped(&self.staging_buffer).await?;
+        let local_buffer = bytemuck::cast_slice(&bytes).to_vec();
 
         Ok(local_buffer)
     }
-}
\ No newline at end of file
+}
+
+/// A [`BufferInfo`] whose binding index and uniform/storage role come from
+/// reflecting `shader.wgsl` (see [`crate::shader_reflect::ShaderReflection`])
+/// instead of being passed in and kept in sync with the shader by hand.
+pub struct TypedBuffer<T: bytemuck::Pod> {
+    info: BufferInfo<T>,
+}
+
+impl<T: bytemuck::Pod> TypedBuffer<T> {
+    /// Registers a buffer for the shader global variable named `name`:
+    /// checks `T`'s size against naga's computed size for that variable's
+    /// WGSL type, looks up its binding index and storage/uniform role, and
+    /// creates the underlying buffer. `indirect` additionally allows it as
+    /// an indirect-dispatch argument buffer.
+    pub fn register(
+        gpu: &GpuDevice,
+        reflection: &ShaderReflection,
+        name: &str,
+        label: Option<&str>,
+        indirect: bool,
+        num_elements: u64,
+    ) -> Self {
+        reflection.verify_pod_size::<T>(name);
+        let binding = reflection.binding_index(name);
+        let role = reflection.buffer_usage(name);
+        TypedBuffer {
+            info: BufferInfo::new(gpu, label, binding, role, indirect, num_elements),
+        }
+    }
+
+    pub fn shader_buffer(&self) -> &Buffer {
+        self.info.shader_buffer()
+    }
+    pub fn byte_size(&self) -> u64 {
+        self.info.byte_size()
+    }
+    pub fn get_bind_group_layout_entry(&self) -> LayoutEntry {
+        self.info.get_bind_group_layout_entry()
+    }
+    pub fn get_bind_group_entry(&self) -> BindEntry {
+        self.info.get_bind_group_entry()
+    }
+    pub fn copy_to_staging_buffer(&self, gpu: &GpuDevice, command_encoder: &mut CommandEncoder) {
+        self.info.copy_to_staging_buffer(gpu, command_encoder)
+    }
+    pub async fn set_uniform_buffer(
+        &self,
+        gpu: &GpuDevice,
+        command_encoder: &mut CommandEncoder,
+        data: &[T],
+    ) -> Result<(), BufferMapError> {
+        self.info.set_uniform_buffer(gpu, command_encoder, data).await
+    }
+    pub async fn read_staging_buffer(&self, gpu: &GpuDevice) -> Result<Vec<T>, BufferMapError> {
+        self.info.read_staging_buffer(gpu).await
+    }
+}
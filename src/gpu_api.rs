@@ -0,0 +1,341 @@
+//! Thin shim around every direct `wgpu::` touchpoint the simulation layer
+//! needs: device/queue creation, buffer/shader-module/layout/pipeline
+//! creation, bind group setup, compute pass dispatch, and the
+//! `map_async`+poll dance used to move data across the staging buffers in
+//! [`crate::buff_utils::BufferInfo`]. `WgpuContext` and `BufferInfo` talk to
+//! the GPU only through [`GpuDevice`] and the opaque handles it hands back
+//! ([`Buffer`], [`ShaderModule`], [`BindGroupLayout`], [`PipelineLayout`],
+//! [`ComputePipeline`], [`BindGroup`], [`CommandEncoder`], [`BufferMapError`]),
+//! so a different WebGPU backend (e.g. Dawn, for performance comparison or
+//! feature availability) could stand in here without the physics layer in
+//! `lib.rs` changing at all, and the mapping dance has exactly one
+//! implementation to replace.
+
+/// Owns the wgpu device and queue and is the only way `lib.rs` and
+/// `buff_utils.rs` reach `wgpu`. [`GpuDevice::limits`] still hands back a
+/// `wgpu::Limits`, and `shader_reflect.rs` names `wgpu::BufferBindingType`
+/// directly.
+pub struct GpuDevice {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+}
+
+/// Opaque handle to a compiled compute pipeline. `lib.rs` stores these and
+/// passes them back into [`GpuDevice::dispatch`]/[`GpuDevice::dispatch_indirect`]
+/// without ever naming `wgpu::ComputePipeline` itself.
+pub struct ComputePipeline(wgpu::ComputePipeline);
+
+/// Opaque handle to a bind group. `lib.rs` stores these and passes them back
+/// into [`GpuDevice::dispatch`]/[`GpuDevice::dispatch_indirect`] without ever
+/// naming `wgpu::BindGroup` itself.
+pub struct BindGroup(wgpu::BindGroup);
+
+/// Opaque handle to a GPU-resident buffer (shader-visible or staging).
+/// `lib.rs` and `buff_utils.rs` pass these around without ever naming
+/// `wgpu::Buffer` themselves.
+pub struct Buffer(wgpu::Buffer);
+
+/// Opaque handle to a compiled shader module.
+pub struct ShaderModule(wgpu::ShaderModule);
+
+/// Opaque handle to a bind group layout.
+pub struct BindGroupLayout(wgpu::BindGroupLayout);
+
+/// Opaque handle to a pipeline layout.
+pub struct PipelineLayout(wgpu::PipelineLayout);
+
+/// Opaque handle to a command encoder. `lib.rs` and `buff_utils.rs` build up
+/// a sequence of commands against this and pass it back into
+/// [`GpuDevice::submit`]/[`GpuDevice::submit_and_wait`] without ever naming
+/// `wgpu::CommandEncoder` themselves.
+pub struct CommandEncoder(wgpu::CommandEncoder);
+
+/// Opaque error returned when mapping a buffer for reading or writing fails.
+#[derive(Debug)]
+pub struct BufferMapError(wgpu::BufferAsyncError);
+
+/// Which resource-binding flavor a buffer plays. Decides both its
+/// `BindingType` in a bind group layout and the usage/mapping flags a
+/// backend needs under the hood, so callers in `buff_utils.rs` only ever say
+/// "storage" or "uniform" instead of assembling raw usage bitflags.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BufferRole {
+    Storage,
+    Uniform,
+}
+
+/// One resource slot in a bind group layout: the binding index the shader
+/// declared it at, plus the [`BufferRole`] that decides its `BindingType`.
+pub struct LayoutEntry {
+    pub binding: u32,
+    pub role: BufferRole,
+}
+
+/// One resource bound into a bind group at creation time.
+pub struct BindEntry<'a> {
+    pub binding: u32,
+    pub buffer: &'a Buffer,
+}
+
+impl GpuDevice {
+    /// Requests an adapter and device from the default wgpu instance.
+    pub async fn request() -> GpuDevice {
+        let instance = wgpu::Instance::default();
+        let adapter = instance
+            .request_adapter(&wgpu::RequestAdapterOptions::default())
+            .await
+            .unwrap();
+        let (device, queue) = adapter
+            .request_device(
+                &wgpu::DeviceDescriptor {
+                    label: None,
+                    required_features: wgpu::Features::empty(),
+                    required_limits: wgpu::Limits::downlevel_defaults(),
+                    memory_hints: wgpu::MemoryHints::Performance,
+                },
+                None,
+            )
+            .await
+            .unwrap();
+        GpuDevice { device, queue }
+    }
+
+    pub fn limits(&self) -> wgpu::Limits {
+        self.device.limits()
+    }
+
+    /// Compiles `source` (WGSL text) labeled `label` into a shader module.
+    pub fn create_shader_module(&self, label: &str, source: &str) -> ShaderModule {
+        ShaderModule(self.device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some(label),
+            source: wgpu::ShaderSource::Wgsl(source.into()),
+        }))
+    }
+
+    /// Creates the GPU-side half of a buffer playing `role`, sized for
+    /// `size` bytes. `indirect` additionally allows it as an
+    /// indirect-dispatch argument buffer.
+    pub fn create_shader_buffer(
+        &self,
+        label: Option<&str>,
+        role: BufferRole,
+        indirect: bool,
+        size: u64,
+    ) -> Buffer {
+        let mut usage = match role {
+            BufferRole::Storage => {
+                wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::COPY_DST
+            }
+            BufferRole::Uniform => wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        };
+        if indirect {
+            usage |= wgpu::BufferUsages::INDIRECT;
+        }
+        Buffer(self.device.create_buffer(&wgpu::BufferDescriptor {
+            label,
+            size,
+            usage,
+            mapped_at_creation: false,
+        }))
+    }
+
+    /// Creates the CPU-mappable staging buffer paired with a `role` shader
+    /// buffer: readable back for storage, writable into for uniform.
+    pub fn create_staging_buffer(&self, label: Option<&str>, role: BufferRole, size: u64) -> Buffer {
+        let usage = match role {
+            BufferRole::Storage => wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            BufferRole::Uniform => wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::MAP_WRITE,
+        };
+        Buffer(self.device.create_buffer(&wgpu::BufferDescriptor {
+            label,
+            size,
+            usage,
+            mapped_at_creation: false,
+        }))
+    }
+
+    /// Creates a buffer pre-filled with `contents`, for the handful of
+    /// uniforms (`system_info`, `grid_params`) written once up front rather
+    /// than through [`crate::buff_utils::TypedBuffer::set_uniform_buffer`].
+    pub fn create_buffer_init(&self, label: Option<&str>, role: BufferRole, contents: &[u8]) -> Buffer {
+        use wgpu::util::DeviceExt;
+        let usage = match role {
+            BufferRole::Storage => wgpu::BufferUsages::STORAGE,
+            BufferRole::Uniform => wgpu::BufferUsages::UNIFORM,
+        };
+        Buffer(self.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label,
+            contents,
+            usage,
+        }))
+    }
+
+    /// Copies the first `size` bytes of `src` into `dst`. The one place
+    /// `copy_buffer_to_buffer` is called.
+    pub fn copy_buffer(&self, encoder: &mut CommandEncoder, src: &Buffer, dst: &Buffer, size: u64) {
+        encoder.0.copy_buffer_to_buffer(&src.0, 0, &dst.0, 0, size);
+    }
+
+    /// Builds a bind group layout from `entries`, in the order given.
+    pub fn create_bind_group_layout(&self, entries: &[LayoutEntry]) -> BindGroupLayout {
+        let entries: Vec<wgpu::BindGroupLayoutEntry> = entries
+            .iter()
+            .map(|entry| wgpu::BindGroupLayoutEntry {
+                binding: entry.binding,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Buffer {
+                    ty: match entry.role {
+                        BufferRole::Storage => wgpu::BufferBindingType::Storage { read_only: false },
+                        BufferRole::Uniform => wgpu::BufferBindingType::Uniform,
+                    },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            })
+            .collect();
+        BindGroupLayout(self.device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: None,
+            entries: &entries,
+        }))
+    }
+
+    /// Ties `entries`' buffers into `layout`'s binding slots.
+    pub fn create_bind_group(&self, layout: &BindGroupLayout, entries: &[BindEntry]) -> BindGroup {
+        let entries: Vec<wgpu::BindGroupEntry> = entries
+            .iter()
+            .map(|entry| wgpu::BindGroupEntry {
+                binding: entry.binding,
+                resource: entry.buffer.0.as_entire_binding(),
+            })
+            .collect();
+        BindGroup(self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout: &layout.0,
+            entries: &entries,
+        }))
+    }
+
+    /// Builds a pipeline layout around a single bind group layout, which is
+    /// all this crate's kernels ever need.
+    pub fn create_pipeline_layout(&self, bind_group_layout: &BindGroupLayout) -> PipelineLayout {
+        PipelineLayout(self.device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[&bind_group_layout.0],
+            push_constant_ranges: &[],
+        }))
+    }
+
+    /// Compiles `module`'s `entry_point` against `layout` into a compute
+    /// pipeline.
+    pub fn create_compute_pipeline(
+        &self,
+        layout: &PipelineLayout,
+        module: &ShaderModule,
+        entry_point: &str,
+    ) -> ComputePipeline {
+        ComputePipeline(self.device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: None,
+            layout: Some(&layout.0),
+            module: &module.0,
+            entry_point: Some(entry_point),
+            compilation_options: Default::default(),
+            cache: None,
+        }))
+    }
+
+    pub fn command_encoder(&self) -> CommandEncoder {
+        CommandEncoder(
+            self.device
+                .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None }),
+        )
+    }
+
+    /// Runs `pipeline` over `bind_group` for a single compute pass, dispatched
+    /// directly with `workgroups` counts. The one place `begin_compute_pass`/
+    /// `set_pipeline`/`set_bind_group`/`dispatch_workgroups` are called.
+    pub fn dispatch(
+        &self,
+        encoder: &mut CommandEncoder,
+        pipeline: &ComputePipeline,
+        bind_group: &BindGroup,
+        workgroups: (u32, u32, u32),
+    ) {
+        let mut compute_pass = encoder.0.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: None,
+            timestamp_writes: None,
+        });
+        compute_pass.set_pipeline(&pipeline.0);
+        compute_pass.set_bind_group(0, &bind_group.0, &[]);
+        compute_pass.dispatch_workgroups(workgroups.0, workgroups.1, workgroups.2);
+    }
+
+    /// Runs `pipeline` over `bind_group` for a single compute pass, dispatched
+    /// indirectly from `indirect_buffer` at `indirect_offset`. The one place
+    /// `begin_compute_pass`/`set_pipeline`/`set_bind_group`/
+    /// `dispatch_workgroups_indirect` are called.
+    pub fn dispatch_indirect(
+        &self,
+        encoder: &mut CommandEncoder,
+        pipeline: &ComputePipeline,
+        bind_group: &BindGroup,
+        indirect_buffer: &Buffer,
+        indirect_offset: u64,
+    ) {
+        let mut compute_pass = encoder.0.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: None,
+            timestamp_writes: None,
+        });
+        compute_pass.set_pipeline(&pipeline.0);
+        compute_pass.set_bind_group(0, &bind_group.0, &[]);
+        compute_pass.dispatch_workgroups_indirect(&indirect_buffer.0, indirect_offset);
+    }
+
+    /// Submits `encoder`'s commands without waiting for the GPU.
+    pub fn submit(&self, encoder: CommandEncoder) {
+        self.queue.submit(Some(encoder.0.finish()));
+    }
+
+    /// Submits `encoder`'s commands and blocks until the GPU has caught up.
+    pub fn submit_and_wait(&self, encoder: CommandEncoder) {
+        self.submit(encoder);
+        self.device.poll(wgpu::Maintain::Wait).panic_on_timeout();
+    }
+
+    /// Maps `buffer` for writing, copies `data` into it, and unmaps it. The
+    /// one place the write-side `map_async`-then-poll dance is implemented.
+    pub async fn write_mapped(&self, buffer: &Buffer, data: &[u8]) -> Result<(), BufferMapError> {
+        let slice = buffer.0.slice(..);
+        let (sender, receiver) = flume::bounded(1);
+        slice.map_async(wgpu::MapMode::Write, move |r| {
+            sender.send(r).unwrap();
+        });
+        self.device.poll(wgpu::Maintain::wait()).panic_on_timeout();
+        receiver.recv_async().await.unwrap().map_err(BufferMapError)?;
+        {
+            let mut view = slice.get_mapped_range_mut();
+            view.copy_from_slice(data);
+        }
+        buffer.0.unmap();
+        Ok(())
+    }
+
+    /// Maps `buffer` for reading and copies its bytes out, unmapping after.
+    /// The one place the read-side `map_async`-then-poll dance is
+    /// implemented.
+    pub async fn read_mapped(&self, buffer: &Buffer) -> Result<Vec<u8>, BufferMapError> {
+        let slice = buffer.0.slice(..);
+        let (sender, receiver) = flume::bounded(1);
+        slice.map_async(wgpu::MapMode::Read, move |r| {
+            sender.send(r).unwrap();
+        });
+        self.device.poll(wgpu::Maintain::Wait).panic_on_timeout();
+        receiver.recv_async().await.unwrap().map_err(BufferMapError)?;
+        let bytes = {
+            let view = slice.get_mapped_range();
+            view.to_vec()
+        };
+        buffer.0.unmap();
+        Ok(bytes)
+    }
+}
@@ -1,15 +1,27 @@
 use lazy_static::lazy_static;
 use nalgebra::Matrix2;
-use wgpu::util::DeviceExt;
 mod buff_utils;
+mod gpu_api;
+mod shader_reflect;
 
-use buff_utils::BufferInfo;
+use buff_utils::TypedBuffer;
+use gpu_api::{BindEntry, BindGroup, BufferRole, ComputePipeline, GpuDevice};
+use shader_reflect::ShaderReflection;
 // const OVERFLOW: u32 = 0xffffffff;
 
-// Plan: use 32x32x1 workgroups, with each workgroup size 64x4x1, 
-// and map those threads (262144 total) into 2D grids (512x512).
-
 use std::f32::consts::PI;
+
+/// Upper bound on the number of orbitals/sublattices a model can have.
+/// `SystemInfo::num_bands` (<= MAX_BANDS) is the number actually in use;
+/// the rest of each fixed-size buffer slot is left zeroed. Mirrored in
+/// `shader.wgsl`.
+const MAX_BANDS: usize = 4;
+
+/// Workgroup size the grid kernels are dispatched with. Mirrored in
+/// `shader.wgsl`; used here to turn a [`GridConfig`] into workgroup counts.
+const WORKGROUP_W: u32 = 64;
+const WORKGROUP_H: u32 = 4;
+
 lazy_static! {
 
     static ref SYSTEM_INFO: SystemInfo = {
@@ -45,25 +57,180 @@ lazy_static! {
             b2: [b2_x, b2_y],
             delta: 0.0,
             t: -1.0,
-            _pad0: 0.0,
+            interaction_strength: 1.0,
+            num_bands: 2,
+            _pad1: 0,
         }
     };
 }
+/// Requested resolution of the k-point grid. Every GPU buffer is sized from
+/// this at [`WgpuContext::new`] time instead of the old hardcoded 512x512,
+/// and the kernel dispatch geometry is derived from it on the GPU.
+#[derive(Debug, Copy, Clone)]
+pub struct GridConfig {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Default for GridConfig {
+    fn default() -> Self {
+        GridConfig {
+            width: 512,
+            height: 512,
+        }
+    }
+}
+
+/// Returned by [`WgpuContext::new`] when `grid` is unusable.
+#[derive(Debug, Clone)]
+pub enum GridConfigError {
+    /// `grid` needs more workgroups per dimension than the adapter supports.
+    TooLarge {
+        requested_workgroups: (u32, u32),
+        max_workgroups_per_dimension: u32,
+    },
+    /// `grid.width` or `grid.height` was zero, which would give every
+    /// reduction kernel an empty grid to fold down and never terminate.
+    ZeroSized,
+}
+
+impl std::fmt::Display for GridConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GridConfigError::TooLarge {
+                requested_workgroups,
+                max_workgroups_per_dimension,
+            } => write!(
+                f,
+                "grid resolution needs {}x{} workgroups, but this adapter only supports {} per dimension",
+                requested_workgroups.0, requested_workgroups.1, max_workgroups_per_dimension
+            ),
+            GridConfigError::ZeroSized => {
+                write!(f, "grid width and height must both be non-zero")
+            }
+        }
+    }
+}
+
+impl std::error::Error for GridConfigError {}
+
 pub async fn run() {
     println!("System config:");
     println!("{:?}", *SYSTEM_INFO);
-    let context = WgpuContext::new().await;
+    let context = WgpuContext::new(GridConfig::default())
+        .await
+        .expect("default grid configuration should fit the adapter");
+
+    let result = run_scf(&context, ScfConfig::default()).await;
+    println!(
+        "SCF {} after {} iterations; total energy {:.6}",
+        if result.converged { "converged" } else { "did not converge" },
+        result.iterations,
+        result.total_energy
+    );
+}
 
-    calc_k_grid(&context);
-    calc_initial_eigen(&context);
+/// Settings for the self-consistent mean-field loop driven by [`run_scf`].
+#[derive(Debug, Copy, Clone)]
+pub struct ScfConfig {
+    /// Target average filling per k-point; the chemical potential is
+    /// re-solved to hit this every iteration via [`solve_chemical_potential`].
+    pub target_filling: f32,
+    /// Convergence tolerance for the per-iteration chemical-potential solve.
+    pub mu_tol: f32,
+    /// Linear density-mixing fraction of the new density that is kept.
+    pub mix_alpha: f32,
+    /// Convergence tolerance on the density-matrix RMS per-element difference.
+    pub tol: f32,
+    /// Hard cap on the number of SCF iterations.
+    pub max_iter: u32,
+}
 
-    let chem_potential = 0.0;
-    let density_arr = get_charge_density(&context, chem_potential).await;
-    let density = density_arr.iter().sum::<f32>() / (density_arr.len() as f32);
-    println!("# of k points: {}", density_arr.len());
-    println!("Trying chemical potential: {chem_potential}");
-    println!("charge density {:?}", density);
+impl Default for ScfConfig {
+    fn default() -> Self {
+        ScfConfig {
+            target_filling: 1.0,
+            mu_tol: 1e-4,
+            mix_alpha: 0.3,
+            tol: 1e-5,
+            max_iter: 200,
+        }
+    }
+}
 
+/// The band structure produced by [`run_scf`]; `converged` says whether the
+/// loop met [`ScfConfig::tol`] before hitting [`ScfConfig::max_iter`].
+#[derive(Debug, Clone)]
+pub struct ScfResult {
+    pub eigen: Vec<EigenInfo>,
+    pub total_energy: f32,
+    pub iterations: u32,
+    pub converged: bool,
+}
+
+/// Runs the self-consistent Hartree-Fock loop: build the per-k density
+/// matrix from the occupied eigenvectors, fold it into a Hartree+Exchange
+/// correction to the bare Hamiltonian, re-diagonalize, and mix the density
+/// with the previous iteration until it stops changing.
+pub async fn run_scf(context: &WgpuContext, config: ScfConfig) -> ScfResult {
+    calc_k_grid(context);
+    calc_initial_eigen(context);
+
+    let mut mu = solve_chemical_potential(context, config.target_filling, config.mu_tol).await;
+    build_density_matrix(context, mu).await;
+    copy_density_matrix_to_mix_in(context);
+
+    let mut converged = false;
+    let mut iterations = 0;
+    for iter in 0..config.max_iter {
+        iterations = iter + 1;
+
+        let hartree_occ = compute_hartree_occupations(context).await;
+        set_hartree_occupations(context, hartree_occ).await;
+        build_fock_hamiltonian(context);
+        diagonalize_fock_hamiltonian(context);
+
+        // The Fock correction shifted the bands, so the chemical potential
+        // must be re-pinned to the target filling before rebuilding rho.
+        mu = solve_chemical_potential(context, config.target_filling, config.mu_tol).await;
+        build_density_matrix(context, mu).await;
+
+        let diff = density_matrix_diff(context).await;
+        mix_density(context, config.mix_alpha).await;
+
+        if diff < config.tol {
+            converged = true;
+            break;
+        }
+    }
+
+    let eigen = read_storage_buffer(&context.gpu, &context.energy_eigen_buf_info).await;
+    let h0 = read_storage_buffer(&context.gpu, &context.hamiltonian_buf_info).await;
+    let num_bands = SYSTEM_INFO.num_bands as usize;
+
+    // Summing occupied Fock eigenvalues isn't the Hartree-Fock total energy:
+    // each eigenvalue already includes the mean field of every other
+    // occupied electron, so that sum counts the Hartree+Exchange
+    // interaction energy twice. The standard fix is to average it with the
+    // occupied orbitals' expectation value of the bare Hamiltonian instead.
+    let mut fock_energy_sum = 0.0f32;
+    let mut bare_energy_sum = 0.0f32;
+    for (e, h) in eigen.iter().zip(h0.iter()) {
+        for band in 0..num_bands {
+            if e.eigenvalues[band] < mu {
+                fock_energy_sum += e.eigenvalues[band];
+                bare_energy_sum += orbital_expectation(h, &e.eigenvectors[band], num_bands);
+            }
+        }
+    }
+    let total_energy = 0.5 * (fock_energy_sum + bare_energy_sum);
+
+    ScfResult {
+        eigen,
+        total_energy,
+        iterations,
+        converged,
+    }
 }
 
 #[repr(C)]
@@ -78,278 +245,662 @@ struct SystemInfo {
     delta: f32,
     t: f32,
     area_unit_cell: f32,
-    _pad0: f32,
+    interaction_strength: f32,
+    num_bands: u32,
+    _pad1: u32,
 }
 
+// An NxN complex Hermitian matrix, flattened row-major; only the leading
+// `num_bands * num_bands` entries are meaningful.
 #[repr(C)]
 #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
-struct H2x2 {
-    h: [[f32; 2]; 4], // 2x2 complex
+struct HamiltonianMatrix {
+    h: [[f32; 2]; MAX_BANDS * MAX_BANDS],
 }
 
-// DIM: 2x2 h(k) -> 2 eigenvalues
 #[repr(C)]
 #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
-struct EigenInfo {
-    k_value: [f32; 2],            // for mem align padding purpose
-    eigenvalues: [f32; 2],            // two real eigenvalues
-    eigenvectors: [[[f32; 2]; 2]; 2], // two complex 2-dim eigenvectors
+pub struct EigenInfo {
+    pub k_value: [f32; 2],
+    pub eigenvalues: [f32; MAX_BANDS],
+    pub eigenvectors: [[[f32; 2]; MAX_BANDS]; MAX_BANDS], // eigenvectors[n][a] = component a of eigenvector n
 }
 
-fn calc_k_grid(context: &WgpuContext) {
-    let mut command_encoder = context
-        .device
-        .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
-
-    {
-        let mut compute_pass = command_encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
-            label: None,
-            timestamp_writes: None,
-        });
-        compute_pass.set_pipeline(&context.k_grid_pipeline);
-        compute_pass.set_bind_group(0, &context.bind_group, &[]);
-        compute_pass.dispatch_workgroups(32, 32, 1);
+fn complex_mul(a: [f32; 2], b: [f32; 2]) -> [f32; 2] {
+    [a[0] * b[0] - a[1] * b[1], a[0] * b[1] + a[1] * b[0]]
+}
+
+/// `<eigenvector|h|eigenvector>`, used by [`run_scf`] to undo the
+/// double-counting of the Hartree+Exchange energy baked into the Fock
+/// eigenvalues.
+fn orbital_expectation(h: &HamiltonianMatrix, eigenvector: &[[f32; 2]; MAX_BANDS], num_bands: usize) -> f32 {
+    let mut acc = [0.0f32, 0.0f32];
+    for a in 0..num_bands {
+        for b in 0..num_bands {
+            let conj_va = [eigenvector[a][0], -eigenvector[a][1]];
+            let term = complex_mul(complex_mul(conj_va, h.h[a * MAX_BANDS + b]), eigenvector[b]);
+            acc[0] += term[0];
+            acc[1] += term[1];
+        }
     }
-    // We finish the compute pass by dropping it.
+    acc[0]
+}
 
-    // Finalize the command encoder, add the contained commands to the queue and flush.
-    context.queue.submit(Some(command_encoder.finish()));
+fn calc_k_grid(context: &WgpuContext) {
+    let mut command_encoder = context.gpu.command_encoder();
 
-    context.device.poll(wgpu::Maintain::Wait).panic_on_timeout();
+    context.gpu.dispatch_indirect(
+        &mut command_encoder,
+        &context.k_grid_pipeline,
+        &context.bind_group,
+        context.dispatch_args_buf_info.shader_buffer(),
+        0,
+    );
+
+    context.gpu.submit_and_wait(command_encoder);
 }
 
 fn calc_initial_eigen(context: &WgpuContext) {
-    let mut command_encoder = context
-        .device
-        .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
-
-    {
-        let mut compute_pass = command_encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
-            label: None,
-            timestamp_writes: None,
-        });
-        compute_pass.set_pipeline(&context.initial_eigen_pipeline);
-        compute_pass.set_bind_group(0, &context.bind_group, &[]);
-        compute_pass.dispatch_workgroups(32, 32, 1);
+    let mut command_encoder = context.gpu.command_encoder();
+
+    context.gpu.dispatch_indirect(
+        &mut command_encoder,
+        &context.initial_eigen_pipeline,
+        &context.bind_group,
+        context.dispatch_args_buf_info.shader_buffer(),
+        0,
+    );
+
+    context.gpu.submit_and_wait(command_encoder);
+}
+
+async fn dispatch_calc_charge_density(context: &WgpuContext, chem_potential: f32) {
+    let mut command_encoder = context.gpu.command_encoder();
+
+    context
+        .chem_potential_guess_buf_info
+        .set_uniform_buffer(&context.gpu, &mut command_encoder, &[chem_potential])
+        .await
+        .unwrap();
+
+    context.gpu.dispatch_indirect(
+        &mut command_encoder,
+        &context.calc_charge_density_pipeline,
+        &context.bind_group,
+        context.dispatch_args_buf_info.shader_buffer(),
+        0,
+    );
+
+    context.gpu.submit_and_wait(command_encoder);
+}
+
+/// Sums `charge_density_buf_info` on the GPU via a multi-pass tree reduction,
+/// so that repeated callers (the chemical-potential bisection, the SCF loop)
+/// only ever map back a single f32 instead of round-tripping the whole
+/// density grid.
+async fn reduce_charge_density_sum(context: &WgpuContext) -> f32 {
+    const REDUCE_WORKGROUP_SIZE: u32 = 256;
+    let grid_len = context.grid.width as u64 * context.grid.height as u64;
+
+    let mut count = grid_len as u32;
+    let mut source = 0u32;
+    loop {
+        let workgroups = count.div_ceil(REDUCE_WORKGROUP_SIZE);
+
+        let mut command_encoder = context.gpu.command_encoder();
+        context
+            .reduce_params_buf_info
+            .set_uniform_buffer(&context.gpu, &mut command_encoder, &[[count, source]])
+            .await
+            .unwrap();
+        context.gpu.dispatch(
+            &mut command_encoder,
+            &context.reduce_sum_pipeline,
+            &context.bind_group,
+            (workgroups, 1, 1),
+        );
+        context.gpu.submit_and_wait(command_encoder);
+
+        let output_in_a = source == 0 || source == 2;
+        if workgroups == 1 {
+            let result = if output_in_a {
+                read_storage_buffer(&context.gpu, &context.partials_a_buf_info).await
+            } else {
+                read_storage_buffer(&context.gpu, &context.partials_b_buf_info).await
+            };
+            return result[0];
+        }
+
+        count = workgroups;
+        source = if output_in_a { 1 } else { 2 };
     }
-    // We finish the compute pass by dropping it.
+}
 
-    // Finalize the command encoder, add the contained commands to the queue and flush.
-    context.queue.submit(Some(command_encoder.finish()));
+/// Dispatches `calc_charge_density` at the given chemical potential and
+/// returns the average filling per k-point, without reading the full
+/// density grid back to the CPU.
+async fn charge_density_filling(context: &WgpuContext, chem_potential: f32) -> f32 {
+    dispatch_calc_charge_density(context, chem_potential).await;
+    let total = reduce_charge_density_sum(context).await;
+    total / (context.grid.width as f32 * context.grid.height as f32)
+}
+
+/// Solves for the chemical potential that yields `target_filling` average
+/// occupation per k-point, via bisection on [`charge_density_filling`]. The
+/// bracket is seeded from the min/max eigenvalues already sitting in
+/// `energy_eigen_buf_info`, since the true chemical potential always lies
+/// within the band spectrum.
+pub async fn solve_chemical_potential(context: &WgpuContext, target_filling: f32, tol: f32) -> f32 {
+    let eigen = read_storage_buffer(&context.gpu, &context.energy_eigen_buf_info).await;
+    let num_bands = SYSTEM_INFO.num_bands as usize;
+    let mut lo = eigen
+        .iter()
+        .flat_map(|e| &e.eigenvalues[..num_bands])
+        .fold(f32::INFINITY, |acc, &e| acc.min(e));
+    let mut hi = eigen
+        .iter()
+        .flat_map(|e| &e.eigenvalues[..num_bands])
+        .fold(f32::NEG_INFINITY, |acc, &e| acc.max(e));
+
+    let mut mu = 0.5 * (lo + hi);
+    const MAX_BISECTION_STEPS: u32 = 64;
+    for _ in 0..MAX_BISECTION_STEPS {
+        mu = 0.5 * (lo + hi);
+        let filling = charge_density_filling(context, mu).await;
+
+        if (filling - target_filling).abs() < tol {
+            break;
+        }
+        if filling > target_filling {
+            hi = mu;
+        } else {
+            lo = mu;
+        }
+    }
+    mu
+}
 
-    context.device.poll(wgpu::Maintain::Wait).panic_on_timeout();
+/// Copies a storage buffer to its staging buffer and reads it back to the CPU.
+/// Takes `gpu` directly (rather than a [`WgpuContext`]) so it can also be
+/// used to read back the dispatch-geometry validation flag during
+/// [`WgpuContext::new`], before a `WgpuContext` exists.
+async fn read_storage_buffer<T: bytemuck::Pod>(
+    gpu: &GpuDevice,
+    buf_info: &TypedBuffer<T>,
+) -> Vec<T> {
+    let mut command_encoder = gpu.command_encoder();
+    buf_info.copy_to_staging_buffer(gpu, &mut command_encoder);
+    gpu.submit(command_encoder);
+    buf_info.read_staging_buffer(gpu).await.unwrap()
 }
 
-async fn get_charge_density(context: &WgpuContext, chem_potential: f32) -> Vec<f32> {
-    let mut command_encoder = context
-        .device
-        .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+async fn build_density_matrix(context: &WgpuContext, chem_potential: f32) {
+    let mut command_encoder = context.gpu.command_encoder();
 
-    context.chem_potential_guess_buf_info.set_uniform_buffer(
-        &context.device,
+    context
+        .chem_potential_guess_buf_info
+        .set_uniform_buffer(&context.gpu, &mut command_encoder, &[chem_potential])
+        .await
+        .unwrap();
+
+    context.gpu.dispatch_indirect(
         &mut command_encoder,
-        &[chem_potential],
-    ).await.unwrap();
-
-    {
-        let mut compute_pass = command_encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
-            label: None,
-            timestamp_writes: None,
-        });
-        compute_pass.set_pipeline(&context.calc_charge_density_pipeline);
-        compute_pass.set_bind_group(0, &context.bind_group, &[]);
-        compute_pass.dispatch_workgroups(32, 32, 1);
+        &context.build_density_matrix_pipeline,
+        &context.bind_group,
+        context.dispatch_args_buf_info.shader_buffer(),
+        0,
+    );
+
+    context.gpu.submit_and_wait(command_encoder);
+}
+
+async fn compute_hartree_occupations(context: &WgpuContext) -> [f32; MAX_BANDS] {
+    let rho = read_storage_buffer(&context.gpu, &context.density_matrix_buf_info).await;
+    let num_k = rho.len() as f32;
+    let num_bands = SYSTEM_INFO.num_bands as usize;
+
+    let mut occupations = [0.0; MAX_BANDS];
+    for (band, occ) in occupations.iter_mut().enumerate().take(num_bands) {
+        *occ = rho
+            .iter()
+            .map(|r| r.h[band * MAX_BANDS + band][0])
+            .sum::<f32>()
+            / num_k;
     }
-    // We finish the compute pass by dropping it.
+    occupations
+}
+
+async fn set_hartree_occupations(context: &WgpuContext, occupations: [f32; MAX_BANDS]) {
+    let mut command_encoder = context.gpu.command_encoder();
     context
-        .charge_density_buf_info
-        .copy_to_staging_buffer(&mut command_encoder);
+        .hartree_occupations_buf_info
+        .set_uniform_buffer(&context.gpu, &mut command_encoder, &[occupations])
+        .await
+        .unwrap();
+    context.gpu.submit_and_wait(command_encoder);
+}
+
+fn build_fock_hamiltonian(context: &WgpuContext) {
+    let mut command_encoder = context.gpu.command_encoder();
+
+    context.gpu.dispatch_indirect(
+        &mut command_encoder,
+        &context.build_fock_hamiltonian_pipeline,
+        &context.bind_group,
+        context.dispatch_args_buf_info.shader_buffer(),
+        0,
+    );
+
+    context.gpu.submit_and_wait(command_encoder);
+}
+
+fn diagonalize_fock_hamiltonian(context: &WgpuContext) {
+    let mut command_encoder = context.gpu.command_encoder();
 
-    // Finalize the command encoder, add the contained commands to the queue and flush.
-    context.queue.submit(Some(command_encoder.finish()));
+    context.gpu.dispatch_indirect(
+        &mut command_encoder,
+        &context.diagonalize_fock_pipeline,
+        &context.bind_group,
+        context.dispatch_args_buf_info.shader_buffer(),
+        0,
+    );
+
+    context.gpu.submit_and_wait(command_encoder);
+}
 
-    let density = context
-        .charge_density_buf_info
-        .read_staging_buffer(&context.device)
+fn copy_density_matrix_to_mix_in(context: &WgpuContext) {
+    let mut command_encoder = context.gpu.command_encoder();
+    context.gpu.copy_buffer(
+        &mut command_encoder,
+        context.density_matrix_buf_info.shader_buffer(),
+        context.density_mix_in_buf_info.shader_buffer(),
+        context.density_matrix_buf_info.byte_size(),
+    );
+    context.gpu.submit_and_wait(command_encoder);
+}
+
+/// Reads back the outgoing and incoming density matrices and returns the RMS
+/// per-element difference between them (only the `num_bands * num_bands`
+/// entries of each `MAX_BANDS * MAX_BANDS` matrix that are actually in use),
+/// used as the SCF convergence metric. This is normalized by element count
+/// rather than a raw Frobenius sum so [`ScfConfig::tol`] means the same thing
+/// regardless of grid size or band count.
+async fn density_matrix_diff(context: &WgpuContext) -> f32 {
+    let rho_out = read_storage_buffer(&context.gpu, &context.density_matrix_buf_info).await;
+    let rho_in = read_storage_buffer(&context.gpu, &context.density_mix_in_buf_info).await;
+    let num_bands = SYSTEM_INFO.num_bands as usize;
+
+    let mut sum_sq = 0.0f32;
+    for (a, b) in rho_out.iter().zip(rho_in.iter()) {
+        for row in 0..num_bands {
+            for col in 0..num_bands {
+                let idx = row * MAX_BANDS + col;
+                sum_sq += (a.h[idx][0] - b.h[idx][0]).powi(2) + (a.h[idx][1] - b.h[idx][1]).powi(2);
+            }
+        }
+    }
+    let element_count = (rho_out.len() * num_bands * num_bands) as f32;
+    (sum_sq / element_count).sqrt()
+}
+
+/// Applies linear density mixing in-place: `density_mix_in` becomes
+/// `alpha * density_matrix + (1 - alpha) * density_mix_in`, and
+/// `density_matrix` is overwritten with the same mixed result so the next
+/// Fock build sees it.
+async fn mix_density(context: &WgpuContext, alpha: f32) {
+    let mut command_encoder = context.gpu.command_encoder();
+    context
+        .mix_alpha_buf_info
+        .set_uniform_buffer(&context.gpu, &mut command_encoder, &[alpha])
         .await
         .unwrap();
 
-    density
+    context.gpu.dispatch_indirect(
+        &mut command_encoder,
+        &context.mix_density_pipeline,
+        &context.bind_group,
+        context.dispatch_args_buf_info.shader_buffer(),
+        0,
+    );
+
+    context.gpu.submit_and_wait(command_encoder);
 }
 
 /// A convenient way to hold together all the useful wgpu stuff together.
-struct WgpuContext {
-    device: wgpu::Device,
-    queue: wgpu::Queue,
-    k_grid_pipeline: wgpu::ComputePipeline,
-    bind_group: wgpu::BindGroup,
-    k_values_buf_info: BufferInfo<{ 512 * 512 }, [f32; 2]>,
-    hamiltonian_buf_info: BufferInfo<{ 512 * 512 }, H2x2>,
-    energy_eigen_buf_info: BufferInfo<{ 512 * 512 }, EigenInfo>,
-    chem_potential_guess_buf_info: BufferInfo<1, f32>,
-    charge_density_buf_info: BufferInfo<{ 512 * 512 }, f32>,
+pub struct WgpuContext {
+    gpu: GpuDevice,
+    grid: GridConfig,
+    k_grid_pipeline: ComputePipeline,
+    bind_group: BindGroup,
+    k_values_buf_info: TypedBuffer<[f32; 2]>,
+    hamiltonian_buf_info: TypedBuffer<HamiltonianMatrix>,
+    energy_eigen_buf_info: TypedBuffer<EigenInfo>,
+    chem_potential_guess_buf_info: TypedBuffer<f32>,
+    charge_density_buf_info: TypedBuffer<f32>,
+    density_matrix_buf_info: TypedBuffer<HamiltonianMatrix>,
+    fock_hamiltonian_buf_info: TypedBuffer<HamiltonianMatrix>,
+    density_mix_in_buf_info: TypedBuffer<HamiltonianMatrix>,
+    mix_alpha_buf_info: TypedBuffer<f32>,
+    hartree_occupations_buf_info: TypedBuffer<[f32; MAX_BANDS]>,
+    partials_a_buf_info: TypedBuffer<f32>,
+    partials_b_buf_info: TypedBuffer<f32>,
+    reduce_params_buf_info: TypedBuffer<[u32; 2]>,
+    dispatch_args_buf_info: TypedBuffer<DispatchArgs>,
+
+    initial_eigen_pipeline: ComputePipeline,
+    calc_charge_density_pipeline: ComputePipeline,
+    build_density_matrix_pipeline: ComputePipeline,
+    build_fock_hamiltonian_pipeline: ComputePipeline,
+    diagonalize_fock_pipeline: ComputePipeline,
+    mix_density_pipeline: ComputePipeline,
+    reduce_sum_pipeline: ComputePipeline,
+}
+
+// Matches the binary layout wgpu expects for an indirect dispatch command:
+// three tightly packed u32 workgroup counts. Mirrored in `shader.wgsl`.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct DispatchArgs {
+    x: u32,
+    y: u32,
+    z: u32,
+}
 
-    initial_eigen_pipeline: wgpu::ComputePipeline,
-    calc_charge_density_pipeline: wgpu::ComputePipeline,
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct GridParams {
+    grid_w: u32,
+    grid_h: u32,
+    max_workgroups_per_dim: u32,
+    _pad1: u32,
 }
 
 impl WgpuContext {
-    async fn new() -> WgpuContext {
-        let instance = wgpu::Instance::default();
-        let adapter = instance
-            .request_adapter(&wgpu::RequestAdapterOptions::default())
-            .await
-            .unwrap();
-        let (device, queue) = adapter
-            .request_device(
-                &wgpu::DeviceDescriptor {
-                    label: None,
-                    required_features: wgpu::Features::empty(),
-                    required_limits: wgpu::Limits::downlevel_defaults(),
-                    memory_hints: wgpu::MemoryHints::Performance,
-                },
-                None,
-            )
-            .await
-            .unwrap();
+    /// Builds a `WgpuContext` sized for `grid`, failing with
+    /// [`GridConfigError`] if `grid` is zero-sized or its resolution needs
+    /// more workgroups per dimension than the adapter supports.
+    pub async fn new(grid: GridConfig) -> Result<WgpuContext, GridConfigError> {
+        if grid.width == 0 || grid.height == 0 {
+            return Err(GridConfigError::ZeroSized);
+        }
+
+        let gpu = GpuDevice::request().await;
+
+        let max_workgroups_per_dim = gpu.limits().max_compute_workgroups_per_dimension;
 
         // Our shader, kindly compiled with Naga.
-        let shader = device.create_shader_module(wgpu::include_wgsl!("shader.wgsl"));
+        let shader = gpu.create_shader_module("shader.wgsl", include_str!("shader.wgsl"));
+        let reflection = ShaderReflection::parse(include_str!("shader.wgsl"));
+
+        // `system_info` is written once from its initial contents rather
+        // than through `TypedBuffer::set_uniform_buffer`, so it's built with
+        // `create_buffer_init` directly; `verify_pod_size` still checks it
+        // against the shader's `SystemInfo` struct so a field added on one
+        // side without the other panics here instead of corrupting GPU
+        // memory silently.
+        reflection.verify_pod_size::<SystemInfo>("system_info");
+        let system_info_buffer = gpu.create_buffer_init(
+            Some("System Info Buffer"),
+            BufferRole::Uniform,
+            bytemuck::cast_slice(&[*SYSTEM_INFO]),
+        );
 
-        use wgpu::BufferUsages;
-        let system_info_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("System Info Buffer"),
-            contents: bytemuck::cast_slice(&[*SYSTEM_INFO]),
-            usage: wgpu::BufferUsages::UNIFORM,
-        });
+        let grid_len = grid.width as u64 * grid.height as u64;
 
-        let k_values_buf_info = BufferInfo::<{ 512 * 512 }, [f32; 2]>::new(
-            &device,
+        let k_values_buf_info = TypedBuffer::<[f32; 2]>::register(
+            &gpu,
+            &reflection,
+            "k_values",
             Some("K Values"),
-            1,
-            BufferUsages::STORAGE,
+            false,
+            grid_len,
         );
 
-        let hamiltonian_buf_info = BufferInfo::<{ 512 * 512 }, H2x2>::new(
-            &device,
+        let hamiltonian_buf_info = TypedBuffer::<HamiltonianMatrix>::register(
+            &gpu,
+            &reflection,
+            "hamiltonian",
             Some("Hamiltonian"),
-            2,
-            BufferUsages::STORAGE,
+            false,
+            grid_len,
         );
 
-        let energy_eigen_buf_info = BufferInfo::<{ 512 * 512 }, EigenInfo>::new(
-            &device,
+        let energy_eigen_buf_info = TypedBuffer::<EigenInfo>::register(
+            &gpu,
+            &reflection,
+            "energy_eigen",
             Some("Eigen Info"),
-            3,
-            BufferUsages::STORAGE,
+            false,
+            grid_len,
         );
 
-        let chem_potential_guess_buf_info = BufferInfo::<1, f32>::new(
-            &device,
+        let chem_potential_guess_buf_info = TypedBuffer::<f32>::register(
+            &gpu,
+            &reflection,
+            "chem_potential_guess",
             Some("Chem Potential Guess"),
-            4,
-            BufferUsages::UNIFORM,
+            false,
+            1,
         );
 
-        let charge_density_buf_info = BufferInfo::<{ 512 * 512 }, f32>::new(
-            &device,
+        let charge_density_buf_info = TypedBuffer::<f32>::register(
+            &gpu,
+            &reflection,
+            "charge_density",
             Some("Result Charge Density"),
-            5,
-            BufferUsages::STORAGE,
+            false,
+            grid_len,
+        );
+
+        let density_matrix_buf_info = TypedBuffer::<HamiltonianMatrix>::register(
+            &gpu,
+            &reflection,
+            "density_matrix",
+            Some("Density Matrix"),
+            false,
+            grid_len,
+        );
+
+        let fock_hamiltonian_buf_info = TypedBuffer::<HamiltonianMatrix>::register(
+            &gpu,
+            &reflection,
+            "fock_hamiltonian",
+            Some("Fock Hamiltonian"),
+            false,
+            grid_len,
+        );
+
+        let density_mix_in_buf_info = TypedBuffer::<HamiltonianMatrix>::register(
+            &gpu,
+            &reflection,
+            "density_mix_in",
+            Some("Density Matrix (mix input)"),
+            false,
+            grid_len,
+        );
+
+        let mix_alpha_buf_info = TypedBuffer::<f32>::register(
+            &gpu,
+            &reflection,
+            "mix_alpha",
+            Some("Density Mixing Alpha"),
+            false,
+            1,
+        );
+
+        let hartree_occupations_buf_info = TypedBuffer::<[f32; MAX_BANDS]>::register(
+            &gpu,
+            &reflection,
+            "hartree_occupations",
+            Some("Hartree Occupations"),
+            false,
+            1,
+        );
+
+        let partials_a_buf_info = TypedBuffer::<f32>::register(
+            &gpu,
+            &reflection,
+            "partials_a",
+            Some("Reduction Partials A"),
+            false,
+            grid_len.div_ceil(256),
+        );
+
+        let partials_b_buf_info = TypedBuffer::<f32>::register(
+            &gpu,
+            &reflection,
+            "partials_b",
+            Some("Reduction Partials B"),
+            false,
+            grid_len.div_ceil(256),
+        );
+
+        let reduce_params_buf_info = TypedBuffer::<[u32; 2]>::register(
+            &gpu,
+            &reflection,
+            "reduce_params",
+            Some("Reduce Params"),
+            false,
+            1,
+        );
+
+        // Same reasoning as `system_info` above: written once from initial
+        // contents, so it bypasses `TypedBuffer`, but still gets the size
+        // check against the shader's `GridParams` struct.
+        reflection.verify_pod_size::<GridParams>("grid_params");
+        let grid_params_buffer = gpu.create_buffer_init(
+            Some("Grid Params Buffer"),
+            BufferRole::Uniform,
+            bytemuck::cast_slice(&[GridParams {
+                grid_w: grid.width,
+                grid_h: grid.height,
+                max_workgroups_per_dim,
+                _pad1: 0,
+            }]),
+        );
+
+        let dispatch_args_buf_info = TypedBuffer::<DispatchArgs>::register(
+            &gpu,
+            &reflection,
+            "dispatch_args",
+            Some("Dispatch Args"),
+            true,
+            1,
+        );
+
+        let dispatch_valid_buf_info = TypedBuffer::<u32>::register(
+            &gpu,
+            &reflection,
+            "dispatch_valid",
+            Some("Dispatch Valid"),
+            false,
+            1,
         );
 
         // This can be though of as the function signature for our CPU-GPU function.
-        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-            label: None,
-            entries: &[
-                // system_info buffer
-                wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::COMPUTE,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        // Going to have this be None just to be safe.
-                        min_binding_size: None,
-                    },
-                    count: None,
-                },
-                // k_values_buffer
-                k_values_buf_info.get_bind_group_layout_entry(),
-                // hamiltonian buffer
-                hamiltonian_buf_info.get_bind_group_layout_entry(),
-                // eigen_info buffer
-                energy_eigen_buf_info.get_bind_group_layout_entry(),
-                // chem optential guess buffer
-                chem_potential_guess_buf_info.get_bind_group_layout_entry(),
-                // charge density Buffer
-                charge_density_buf_info.get_bind_group_layout_entry(),
-            ],
-        });
+        // Every binding's layout entry is generated straight from
+        // `reflection`, in shader binding-index order, so this can no
+        // longer drift out of lockstep with `shader.wgsl`.
+        let bind_group_layout = gpu.create_bind_group_layout(&reflection.all_layout_entries());
         // This ties actual resources stored in the GPU to our metaphorical function
         // through the binding slots we defined above.
-        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: None,
-            layout: &bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: system_info_buffer.as_entire_binding(),
+        let bind_group = gpu.create_bind_group(
+            &bind_group_layout,
+            &[
+                BindEntry {
+                    binding: reflection.binding_index("system_info"),
+                    buffer: &system_info_buffer,
                 },
                 k_values_buf_info.get_bind_group_entry(),
                 hamiltonian_buf_info.get_bind_group_entry(),
                 energy_eigen_buf_info.get_bind_group_entry(),
                 chem_potential_guess_buf_info.get_bind_group_entry(),
                 charge_density_buf_info.get_bind_group_entry(),
+                density_matrix_buf_info.get_bind_group_entry(),
+                fock_hamiltonian_buf_info.get_bind_group_entry(),
+                density_mix_in_buf_info.get_bind_group_entry(),
+                mix_alpha_buf_info.get_bind_group_entry(),
+                hartree_occupations_buf_info.get_bind_group_entry(),
+                partials_a_buf_info.get_bind_group_entry(),
+                partials_b_buf_info.get_bind_group_entry(),
+                reduce_params_buf_info.get_bind_group_entry(),
+                BindEntry {
+                    binding: reflection.binding_index("grid_params"),
+                    buffer: &grid_params_buffer,
+                },
+                dispatch_args_buf_info.get_bind_group_entry(),
+                dispatch_valid_buf_info.get_bind_group_entry(),
             ],
-        });
-
-        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-            label: None,
-            bind_group_layouts: &[&bind_group_layout],
-            push_constant_ranges: &[],
-        });
-        let k_grid_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
-            label: None,
-            layout: Some(&pipeline_layout),
-            module: &shader,
-            entry_point: Some("compute_k_grid"),
-            compilation_options: Default::default(),
-            cache: None,
-        });
-        let initial_eigen_pipeline =
-            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
-                label: None,
-                layout: Some(&pipeline_layout),
-                module: &shader,
-                entry_point: Some("calc_initial_eigen"),
-                compilation_options: Default::default(),
-                cache: None,
-            });
+        );
 
+        let pipeline_layout = gpu.create_pipeline_layout(&bind_group_layout);
+        let k_grid_pipeline = gpu.create_compute_pipeline(&pipeline_layout, &shader, "compute_k_grid");
+        let initial_eigen_pipeline =
+            gpu.create_compute_pipeline(&pipeline_layout, &shader, "calc_initial_eigen");
         let calc_charge_density_pipeline =
-            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
-                label: None,
-                layout: Some(&pipeline_layout),
-                module: &shader,
-                entry_point: Some("calc_charge_density"),
-                compilation_options: Default::default(),
-                cache: None,
+            gpu.create_compute_pipeline(&pipeline_layout, &shader, "calc_charge_density");
+        let build_density_matrix_pipeline =
+            gpu.create_compute_pipeline(&pipeline_layout, &shader, "build_density_matrix");
+        let build_fock_hamiltonian_pipeline =
+            gpu.create_compute_pipeline(&pipeline_layout, &shader, "build_fock_hamiltonian");
+        let diagonalize_fock_pipeline =
+            gpu.create_compute_pipeline(&pipeline_layout, &shader, "diagonalize_fock_hamiltonian");
+        let mix_density_pipeline = gpu.create_compute_pipeline(&pipeline_layout, &shader, "mix_density");
+        let reduce_sum_pipeline = gpu.create_compute_pipeline(&pipeline_layout, &shader, "reduce_sum");
+        let compute_dispatch_geometry_pipeline =
+            gpu.create_compute_pipeline(&pipeline_layout, &shader, "compute_dispatch_geometry");
+
+        // Derive the indirect dispatch geometry for `grid` on the GPU, and
+        // check it fits the adapter before any real kernel reads it.
+        {
+            let mut command_encoder = gpu.command_encoder();
+            gpu.dispatch(
+                &mut command_encoder,
+                &compute_dispatch_geometry_pipeline,
+                &bind_group,
+                (1, 1, 1),
+            );
+            gpu.submit_and_wait(command_encoder);
+        }
+
+        let valid = read_storage_buffer(&gpu, &dispatch_valid_buf_info).await;
+        if valid[0] == 0 {
+            return Err(GridConfigError::TooLarge {
+                requested_workgroups: (
+                    grid.width.div_ceil(WORKGROUP_W),
+                    grid.height.div_ceil(WORKGROUP_H),
+                ),
+                max_workgroups_per_dimension: max_workgroups_per_dim,
             });
+        }
 
-        WgpuContext {
-            device,
-            queue,
+        Ok(WgpuContext {
+            gpu,
+            grid,
             k_values_buf_info,
             hamiltonian_buf_info,
             energy_eigen_buf_info,
             chem_potential_guess_buf_info,
             charge_density_buf_info,
+            density_matrix_buf_info,
+            fock_hamiltonian_buf_info,
+            density_mix_in_buf_info,
+            mix_alpha_buf_info,
+            hartree_occupations_buf_info,
+            partials_a_buf_info,
+            partials_b_buf_info,
+            reduce_params_buf_info,
+            dispatch_args_buf_info,
 
             k_grid_pipeline,
             initial_eigen_pipeline,
             calc_charge_density_pipeline,
+            build_density_matrix_pipeline,
+            build_fock_hamiltonian_pipeline,
+            diagonalize_fock_pipeline,
+            mix_density_pipeline,
+            reduce_sum_pipeline,
             bind_group,
-        }
+        })
     }
 }
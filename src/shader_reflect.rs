@@ -0,0 +1,137 @@
+//! Reflects `shader.wgsl`'s `@group(0)` bindings via `naga` so
+//! [`WgpuContext::new`](crate::WgpuContext::new) doesn't have to keep a
+//! hand-written `BindGroupLayoutEntry` list and a set of binding-index
+//! literals in lockstep with the shader source. Buffers are registered by
+//! the shader variable's name through [`crate::buff_utils::TypedBuffer`]
+//! instead, and each bound Rust struct's size and alignment are checked
+//! against naga's computed layout for the matching WGSL type, so a
+//! struct-layout drift is a panic at construction instead of silently
+//! corrupted GPU memory.
+
+use std::collections::HashMap;
+
+use crate::gpu_api::{BufferRole, LayoutEntry};
+
+struct ReflectedBinding {
+    binding: u32,
+    ty: wgpu::BufferBindingType,
+    /// Size naga computed for the bound type: the struct size for a uniform,
+    /// or the element stride for a storage `array<T>`.
+    size: u64,
+    /// Alignment naga computed for the bound type. Two structs can share a
+    /// total size while differing in field order/padding (WGSL's vec2/vec4
+    /// alignment rules routinely insert gaps `#[repr(C)]` wouldn't), so this
+    /// is checked alongside `size` in [`ShaderReflection::verify_pod_size`].
+    align: u64,
+}
+
+/// The `@group(0)` resource bindings of `shader.wgsl`, keyed by variable
+/// name, parsed once via naga's WGSL frontend.
+pub struct ShaderReflection {
+    bindings: HashMap<String, ReflectedBinding>,
+}
+
+impl ShaderReflection {
+    /// Parses `source` and reflects every named, bound global variable.
+    /// Panics if `source` isn't valid WGSL, since that means `shader.wgsl`
+    /// itself is broken.
+    pub fn parse(source: &str) -> Self {
+        let module =
+            naga::front::wgsl::parse_str(source).expect("shader.wgsl should be valid WGSL");
+
+        let mut layouter = naga::proc::Layouter::default();
+        layouter
+            .update(module.to_ctx())
+            .expect("naga layout computation should succeed for shader.wgsl");
+
+        let mut bindings = HashMap::new();
+        for (_, var) in module.global_variables.iter() {
+            let Some(binding) = &var.binding else {
+                continue;
+            };
+            let ty = match &var.space {
+                naga::AddressSpace::Uniform => wgpu::BufferBindingType::Uniform,
+                naga::AddressSpace::Storage { access } => wgpu::BufferBindingType::Storage {
+                    read_only: !access.contains(naga::StorageAccess::STORE),
+                },
+                _ => continue,
+            };
+            let name = var
+                .name
+                .clone()
+                .expect("a bound global variable should be named");
+            let size = layouter[var.ty].size as u64;
+            let align = layouter[var.ty].alignment.round_up(1) as u64;
+
+            bindings.insert(
+                name,
+                ReflectedBinding {
+                    binding: binding.binding,
+                    ty,
+                    size,
+                    align,
+                },
+            );
+        }
+
+        ShaderReflection { bindings }
+    }
+
+    fn get(&self, name: &str) -> &ReflectedBinding {
+        self.bindings
+            .get(name)
+            .unwrap_or_else(|| panic!("shader.wgsl has no bound global variable named `{name}`"))
+    }
+
+    /// The `@binding(N)` index of the global variable named `name`.
+    pub fn binding_index(&self, name: &str) -> u32 {
+        self.get(name).binding
+    }
+
+    /// The [`BufferRole`] (`Storage` or `Uniform`) implied by `name`'s WGSL
+    /// address space.
+    pub fn buffer_usage(&self, name: &str) -> BufferRole {
+        match self.get(name).ty {
+            wgpu::BufferBindingType::Uniform => BufferRole::Uniform,
+            wgpu::BufferBindingType::Storage { .. } => BufferRole::Storage,
+        }
+    }
+
+    /// The [`LayoutEntry`] for the global variable named `name`.
+    pub fn layout_entry(&self, name: &str) -> LayoutEntry {
+        LayoutEntry {
+            binding: self.binding_index(name),
+            role: self.buffer_usage(name),
+        }
+    }
+
+    /// All reflected bindings' layout entries, sorted by binding index so
+    /// the bind group layout can be built straight from the shader instead
+    /// of from a hand-maintained list.
+    pub fn all_layout_entries(&self) -> Vec<LayoutEntry> {
+        let mut names: Vec<&String> = self.bindings.keys().collect();
+        names.sort_by_key(|name| self.get(name).binding);
+        names.into_iter().map(|name| self.layout_entry(name)).collect()
+    }
+
+    /// Panics if `T`'s size or alignment doesn't match what naga computed
+    /// for the WGSL type bound to `name`. Size alone isn't enough: two
+    /// structs can share a total size while differing in field order or
+    /// internal padding, so a size-only check would pass while still
+    /// binding a Rust struct whose layout doesn't match the shader's.
+    pub fn verify_pod_size<T: bytemuck::Pod>(&self, name: &str) {
+        let info = self.get(name);
+        let rust_size = std::mem::size_of::<T>() as u64;
+        assert_eq!(
+            rust_size, info.size,
+            "binding `{name}`: Rust type is {rust_size} bytes but shader.wgsl's matching type is {} bytes",
+            info.size
+        );
+        let rust_align = std::mem::align_of::<T>() as u64;
+        assert_eq!(
+            rust_align, info.align,
+            "binding `{name}`: Rust type has alignment {rust_align} but shader.wgsl's matching type has alignment {}",
+            info.align
+        );
+    }
+}